@@ -0,0 +1,326 @@
+use super::*;
+use shipyard::*;
+
+/// Managed by the ancestor_indexing system to answer depth/ancestor/LCA queries in O(log n)
+/// instead of walking `ChildOf` to the root each time.
+///
+/// `up[k]` is the 2^k-th ancestor of the node (binary lifting), built lazily up to however many
+/// levels the node's depth actually needs.
+#[derive(Debug)]
+pub struct AncestorIndex {
+    pub depth: u32,
+    up: Vec<Option<ID>>,
+}
+
+/// Indexes `ChildOf` into per-node depth and a binary-lifting ancestor table, alongside
+/// `tree_indexing`.
+///
+/// Reuses the same `inserted()/modified()` change tracking: any node whose `ChildOf` changed has
+/// its own `AncestorIndex` (and, transitively, its whole subtree's, since depth propagates down)
+/// rebuilt via `transform_with_payload`. Deletions are read from `DeletedChildOf` rather than
+/// `v_child_of.deleted()` directly, since `tree_indexing` already drains that with
+/// `take_deleted()` -- this system must run after `tree_indexing` in the workload to see them.
+///
+/// Note: deleting a `ChildOf` only removes that node's own `AncestorIndex` entry, the same way
+/// `tree_indexing`'s `unlink_child` only unlinks the node itself — any remaining descendants keep
+/// stale depths/tables until their own `ChildOf` is touched.
+pub fn ancestor_indexing(
+    (v_entities, v_child_of, v_parent_index, deleted_child_of, mut vm_ancestor_index): (
+        EntitiesView,
+        View<ChildOf>,
+        View<ParentIndex>,
+        UniqueView<DeletedChildOf>,
+        ViewMut<AncestorIndex>,
+    ),
+) {
+    deleted_child_of
+        .0
+        .iter()
+        .for_each(|(deleted_id, _)| {
+            vm_ancestor_index.delete(*deleted_id);
+        });
+
+    v_child_of
+        .inserted()
+        .iter()
+        .with_id()
+        .into_iter()
+        .map(|(id, _)| id)
+        .chain(
+            v_child_of
+                .modified()
+                .iter()
+                .with_id()
+                .into_iter()
+                .map(|(id, _)| id),
+        )
+        .for_each(|changed_id| {
+            rebuild_subtree(
+                &v_entities,
+                &v_child_of,
+                &v_parent_index,
+                &mut vm_ancestor_index,
+                changed_id,
+            );
+        });
+}
+
+fn rebuild_subtree(
+    v_entities: &EntitiesView,
+    v_child_of: &View<ChildOf>,
+    v_parent_index: &View<ParentIndex>,
+    vm_ancestor_index: &mut ViewMut<AncestorIndex>,
+    root: ID,
+) {
+    let root_parent = v_child_of
+        .try_get(root)
+        .ok()
+        .map(|ChildOf(parent_id, _)| *parent_id);
+
+    transform_with_payload(
+        v_parent_index,
+        root,
+        root_parent,
+        |id, parent: Option<ID>| {
+            let up = build_up_table(&*vm_ancestor_index, parent);
+            let depth = match parent {
+                // the parent has no `ChildOf` of its own, so it's the tree's (implicit,
+                // never-indexed) root: depth 0 for it is exact, not a guess, making this node's
+                // depth 1 -- not the "unindexed, default to 0" fallback below
+                Some(parent_id) if v_child_of.try_get(parent_id).is_err() => 1,
+                Some(parent_id) => vm_ancestor_index
+                    .try_get(parent_id)
+                    .map(|a| a.depth + 1)
+                    .unwrap_or(0),
+                None => 0,
+            };
+
+            let num_children = v_parent_index
+                .try_get(id)
+                .map(|parent_index| parent_index.children.len())
+                .unwrap_or(0);
+
+            v_entities.add_component(&mut *vm_ancestor_index, AncestorIndex { depth, up }, id);
+
+            ((), vec![Some(id); num_children])
+        },
+        |_id, (), _children_up: Vec<()>| (),
+    );
+}
+
+/// Builds the binary-lifting table for a node given its immediate parent, reusing its ancestors'
+/// already-built tables (`up[level + 1] = ancestor_index(up[level]).up[level]`).
+fn build_up_table(vm_ancestor_index: &ViewMut<AncestorIndex>, parent: Option<ID>) -> Vec<Option<ID>> {
+    let mut up = match parent {
+        Some(parent_id) => vec![Some(parent_id)],
+        None => return Vec::new(),
+    };
+
+    loop {
+        let level = up.len() - 1;
+        let prev_id = match up[level] {
+            Some(prev_id) => prev_id,
+            None => break,
+        };
+
+        let next = vm_ancestor_index
+            .try_get(prev_id)
+            .ok()
+            .and_then(|ancestor| ancestor.up.get(level).copied().flatten());
+
+        match next {
+            Some(next_id) => up.push(Some(next_id)),
+            None => break,
+        }
+    }
+
+    up
+}
+
+/// Depth of `id` in its tree (root is depth 0). Defaults to 0 for a node with no index yet.
+pub fn depth(v_ancestor_index: &View<AncestorIndex>, id: ID) -> u32 {
+    v_ancestor_index.try_get(id).map(|a| a.depth).unwrap_or(0)
+}
+
+/// The ancestor of `id` that is `levels` steps up, or `None` if that goes past the root.
+pub fn ancestor(v_ancestor_index: &View<AncestorIndex>, id: ID, levels: u32) -> Option<ID> {
+    let mut current = id;
+    let mut remaining = levels;
+    let mut level = 0;
+
+    while remaining > 0 {
+        if remaining & 1 == 1 {
+            current = v_ancestor_index
+                .try_get(current)
+                .ok()?
+                .up
+                .get(level)
+                .copied()
+                .flatten()?;
+        }
+        remaining >>= 1;
+        level += 1;
+    }
+
+    Some(current)
+}
+
+/// Whether `ancestor_id` is a (possibly indirect) ancestor of `id`. `id` counts as its own
+/// ancestor, so this is `true` when the two are equal.
+pub fn is_ancestor_of(v_ancestor_index: &View<AncestorIndex>, ancestor_id: ID, id: ID) -> bool {
+    // `depth()` already defaults an unindexed entity (e.g. the tree's implicit, never-indexed
+    // root) to depth 0, so reuse it here instead of treating a missing `AncestorIndex` as "not
+    // an ancestor"
+    let ancestor_depth = depth(v_ancestor_index, ancestor_id);
+    let id_depth = depth(v_ancestor_index, id);
+
+    id_depth >= ancestor_depth
+        && ancestor(v_ancestor_index, id, id_depth - ancestor_depth) == Some(ancestor_id)
+}
+
+/// Lowest common ancestor of `a` and `b`, via binary lifting: first bring the deeper node up to
+/// the shallower node's depth, then, unless they're already equal, jump both up together by
+/// decreasing powers of two whenever their ancestors at that jump still differ.
+pub fn lca(v_ancestor_index: &View<AncestorIndex>, a: ID, b: ID) -> Option<ID> {
+    // as in `is_ancestor_of`, default to depth 0 via `depth()` rather than failing outright, so
+    // the tree's implicit root (which never gets its own `AncestorIndex`) still participates
+    let depth_a = depth(v_ancestor_index, a);
+    let depth_b = depth(v_ancestor_index, b);
+
+    let (mut deeper, mut shallower) = if depth_a >= depth_b { (a, b) } else { (b, a) };
+    let diff = depth_a.max(depth_b) - depth_a.min(depth_b);
+    deeper = ancestor(v_ancestor_index, deeper, diff)?;
+
+    if deeper == shallower {
+        return Some(deeper);
+    }
+
+    let max_level = v_ancestor_index.try_get(deeper).ok()?.up.len();
+    for level in (0..max_level).rev() {
+        let deeper_next = v_ancestor_index
+            .try_get(deeper)
+            .ok()
+            .and_then(|a| a.up.get(level).copied().flatten());
+        let shallower_next = v_ancestor_index
+            .try_get(shallower)
+            .ok()
+            .and_then(|a| a.up.get(level).copied().flatten());
+
+        if let (Some(d), Some(s)) = (deeper_next, shallower_next) {
+            if d != s {
+                deeper = d;
+                shallower = s;
+            }
+        }
+    }
+
+    ancestor(v_ancestor_index, deeper, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Same childless-root shape as `tests::test_indexing` in `lib.rs`: the tree's root entity
+    /// never gets a `ChildOf`, so it never gets its own `AncestorIndex` either.
+    #[test]
+    fn depth_and_lca_handle_a_childless_root() {
+        let world = World::new();
+        world.add_unique(ReorderCommands(vec![]));
+        world.add_unique(PrunedForest::default());
+        world.add_unique(DeletedChildOf::default());
+        world.run(|mut vm_child_of: ViewMut<ChildOf>| {
+            vm_child_of.update_pack();
+        });
+
+        world
+            .add_workload("tests")
+            .with_system(system!(tree_reordering))
+            .with_system(system!(tree_indexing))
+            .with_system(system!(ancestor_indexing))
+            .build();
+
+        let (root, a1, a2, a1b, a1b1) = world.run(
+            |mut entities: EntitiesViewMut, mut vm_child_of: ViewMut<ChildOf>| {
+                let root = entities.add_entity((), ());
+                let a1 = entities.add_entity(&mut vm_child_of, ChildOf(root, Ordered::hinted(1)));
+                let a2 = entities.add_entity(&mut vm_child_of, ChildOf(root, Ordered::hinted(2)));
+                let a1b = entities.add_entity(&mut vm_child_of, ChildOf(a1, Ordered::hinted(1)));
+                let a1b1 = entities.add_entity(&mut vm_child_of, ChildOf(a1b, Ordered::hinted(1)));
+                (root, a1, a2, a1b, a1b1)
+            },
+        );
+
+        world.run_default();
+
+        world.run(|v_ancestor_index: View<AncestorIndex>| {
+            assert_eq!(depth(&v_ancestor_index, root), 0, "childless root");
+            assert_eq!(depth(&v_ancestor_index, a1), 1, "direct child of the root");
+            assert_eq!(depth(&v_ancestor_index, a2), 1, "direct child of the root");
+            assert_eq!(depth(&v_ancestor_index, a1b), 2);
+            assert_eq!(depth(&v_ancestor_index, a1b1), 3);
+
+            assert_eq!(lca(&v_ancestor_index, a1, a2), Some(root));
+            assert_eq!(lca(&v_ancestor_index, a1b1, a2), Some(root));
+            assert_eq!(lca(&v_ancestor_index, a1, a1b1), Some(a1));
+            assert_eq!(lca(&v_ancestor_index, root, a1b1), Some(root));
+
+            assert!(is_ancestor_of(&v_ancestor_index, root, a1b1));
+            assert!(is_ancestor_of(&v_ancestor_index, a1, a1b1));
+            assert!(!is_ancestor_of(&v_ancestor_index, a2, a1b1));
+        });
+    }
+
+    /// Regression test: `ancestor_indexing` used to read deletions straight off
+    /// `v_child_of.deleted()`, but `tree_indexing` (which runs first in the same workload) already
+    /// drains that buffer with `take_deleted()`, so `ancestor_indexing` always saw zero deletions
+    /// and never cleaned up the deleted node's `AncestorIndex`.
+    #[test]
+    fn deleting_a_child_of_removes_its_ancestor_index() {
+        let world = World::new();
+        world.add_unique(ReorderCommands(vec![]));
+        world.add_unique(PrunedForest::default());
+        world.add_unique(DeletedChildOf::default());
+        world.run(|mut vm_child_of: ViewMut<ChildOf>| {
+            vm_child_of.update_pack();
+        });
+
+        world
+            .add_workload("tests")
+            .with_system(system!(tree_reordering))
+            .with_system(system!(tree_indexing))
+            .with_system(system!(ancestor_indexing))
+            .build();
+
+        let (root, a, b) = world.run(
+            |mut entities: EntitiesViewMut, mut vm_child_of: ViewMut<ChildOf>| {
+                let root = entities.add_entity((), ());
+                let a = entities.add_entity(&mut vm_child_of, ChildOf(root, Ordered::hinted(1)));
+                let b = entities.add_entity(&mut vm_child_of, ChildOf(a, Ordered::hinted(1)));
+                (root, a, b)
+            },
+        );
+
+        world.run_default();
+
+        world.run(|v_ancestor_index: View<AncestorIndex>| {
+            assert_eq!(depth(&v_ancestor_index, root), 0, "childless root");
+            v_ancestor_index.try_get(a).expect("a should be indexed");
+        });
+
+        world.run(|mut vm_child_of: ViewMut<ChildOf>| {
+            vm_child_of.delete(a);
+        });
+
+        world.run_default();
+
+        world.run(|v_ancestor_index: View<AncestorIndex>| {
+            v_ancestor_index
+                .try_get(a)
+                .expect_err("a's AncestorIndex should have been removed");
+            // b is unaffected by design (see ancestor_indexing's doc): only the deleted node's own
+            // entry is cleaned up, not its descendants'
+            v_ancestor_index.try_get(b).expect("b is left as-is");
+        });
+    }
+}
@@ -1,5 +1,7 @@
 use super::*;
+use rayon::prelude::*;
 use shipyard::*;
+use std::collections::{HashMap, HashSet};
 
 // Ordered first in tuple so it takes ordering precedence
 type SiblingID = (Ordered, ID);
@@ -19,184 +21,214 @@ pub struct ParentIndex {
     pub children: Vec<SiblingID>,
 }
 
+/// Above this many siblings, a parent's children get sorted with Rayon's parallel sort instead of
+/// the sequential one; below it, the overhead of spinning up parallel work isn't worth it.
+const PARALLEL_SORT_THRESHOLD: usize = 256;
+
+/// The `ChildOf` deletions `tree_indexing` drained with `take_deleted()` on its most recent run,
+/// restocked every run so any other system in the same workload that also needs to react to a
+/// `ChildOf` deletion (e.g. `ancestor_indexing`) still can, without racing `tree_indexing` to
+/// shipyard's own deletion tracking: `take_deleted()` drains it once, so whichever system reads it
+/// first with `deleted()`/`take_deleted()` would silently starve every system that runs after.
+/// Requires `tree_indexing` to run before any consumer of this in the workload.
+#[derive(Default)]
+pub struct DeletedChildOf(pub Vec<(ID, ChildOf)>);
+
 /// Indexes tree ChildOf and Ordering components into more helpful between nodes
 pub fn tree_indexing(
-    (v_entities, v_child_of, mut vm_sibling_index, mut vm_parent_index): (
+    (v_entities, mut v_child_of, mut vm_sibling_index, mut vm_parent_index, mut deleted_child_of): (
         EntitiesView,
-        View<ChildOf>,
+        ViewMut<ChildOf>,
         ViewMut<SiblingIndex>,
         ViewMut<ParentIndex>,
+        UniqueViewMut<DeletedChildOf>,
     ),
 ) {
-    // iff ChildOf was completely deleted (does not include "removed")
-    v_child_of
-        .deleted()
-        .into_iter()
-        .map(|(id, _)| id)
-        .for_each(|deleted_id: &ID| {
+    // drained with take_deleted() rather than peeked with deleted() so a ChildOf deletion is only
+    // ever unlinked once here -- left as deleted(), the same deletion would resurface and
+    // re-unlink (now-stale) entities on every subsequent tree_indexing run. Stashed in
+    // `deleted_child_of` (see its doc) so later systems in the same workload can still observe
+    // exactly these deletions.
+    deleted_child_of.0 = v_child_of.take_deleted();
+    deleted_child_of
+        .0
+        .iter()
+        .for_each(|(deleted_id, _)| {
             unlink_child(&mut vm_sibling_index, &mut vm_parent_index, *deleted_id);
         });
 
-    // iff ChildOf is completely new component
-    v_child_of.inserted().iter().with_id().into_iter().for_each(
-        |(inserted_id, ChildOf(parent_id, child_order))| {
-            insert_child_of(
-                &v_entities,
-                &v_child_of,
-                &mut vm_sibling_index,
-                &mut vm_parent_index,
-                inserted_id,
-                &child_order,
-                parent_id.clone(),
-            );
-        },
-    );
-
-    // iff ChildOf was modified
-    v_child_of.modified().iter().with_id().into_iter().for_each(
-        |(modified_id, ChildOf(parent_id, child_order))| {
-            dbg!(modified_id);
-
-            // remove from parent
+    // iff ChildOf was modified, unlink from its old parent first; it gets rebucketed by its
+    // (possibly new) parent below just like a fresh insert
+    v_child_of
+        .modified()
+        .iter()
+        .with_id()
+        .into_iter()
+        .for_each(|(modified_id, _)| {
             unlink_child(&mut vm_sibling_index, &mut vm_parent_index, modified_id);
+        });
 
-            // reinsert child
-            insert_child_of(
-                &v_entities,
-                &v_child_of,
-                &mut vm_sibling_index,
-                &mut vm_parent_index,
-                modified_id,
-                &child_order,
-                parent_id.clone(),
-            );
-        },
-    );
+    // Bucket every inserted/modified ChildOf by parent_id in a single pass, instead of the old
+    // insert_child_of which, the first time a parent was touched, re-scanned and re-sorted every
+    // ChildOf in the whole world for that parent alone. Siblings under distinct parents are
+    // independent, so this also sets up each parent's sort to run on its own.
+    let mut changed_by_parent: HashMap<ID, Vec<SiblingID>> = HashMap::new();
+    v_child_of
+        .inserted()
+        .iter()
+        .with_id()
+        .into_iter()
+        .chain(v_child_of.modified().iter().with_id())
+        .for_each(|(id, ChildOf(parent_id, ordered))| {
+            changed_by_parent
+                .entry(*parent_id)
+                .or_default()
+                .push((ordered.clone(), id));
+        });
+
+    for (parent_id, mut new_children) in changed_by_parent {
+        patch_parent_index(
+            &v_entities,
+            &mut vm_sibling_index,
+            &mut vm_parent_index,
+            parent_id,
+            &mut new_children,
+        );
+    }
 }
 
-fn insert_child_of(
+/// Merges `new_children` into `parent_id`'s `ParentIndex` (building one fresh if this is the
+/// first time the parent is touched), sorting once for the whole batch rather than once per
+/// child, and rebuilds the parent's `SiblingIndex` entries to match.
+fn patch_parent_index(
     v_entities: &EntitiesView,
-    v_child_of: &View<ChildOf>, // needed for creating parent node indexes, since parents do not need a ChildOf component
     vm_sibling_index: &mut ViewMut<SiblingIndex>,
     vm_parent_index: &mut ViewMut<ParentIndex>,
-    child_id: ID,
-    child_order: &Ordered, // used to position between siblings
-    parent_id: ID,         // insert to this parent
+    parent_id: ID,
+    new_children: &mut Vec<SiblingID>,
 ) {
-    // parent: insert into list at correct location,
-    // find next index and previous index and update their sibling references respectively
-    let parent_index: &mut ParentIndex = {
-        if let Ok(parent_index) = vm_parent_index.try_get(parent_id) {
+    let mut children = match vm_parent_index.try_get(parent_id) {
+        Ok(parent_index) => {
+            // entries in `new_children` may be stale positions of existing siblings (their
+            // Ordered changed) as well as brand-new ones; drop the stale copy before merging
+            let incoming_ids: HashSet<ID> = new_children.iter().map(|(_, id)| *id).collect();
             parent_index
-        } else {
-            let mut children = v_child_of
-                .iter()
-                .filter(|ChildOf(child_parent_id, _)| child_parent_id == &parent_id)
-                .with_id()
-                .into_iter()
-                .map(|(id, ChildOf(_, ref ordered))| -> SiblingID { (ordered.clone(), id) })
-                .collect::<Vec<SiblingID>>();
-
-            children.sort();
-
-            // we need to create their SiblingIndex components
-            for (idx, child) in children.iter().enumerate() {
-                // dbg!(child);
-                v_entities.add_component(
-                    &mut *vm_sibling_index,
-                    SiblingIndex {
-                        next_sibling: if idx < children.len() - 1 {
-                            Some(children[idx + 1])
-                        } else {
-                            None
-                        },
-                        prev_sibling: if idx > 0 {
-                            Some(children[idx - 1])
-                        } else {
-                            None
-                        },
-                        ordered_node: child.clone(),
-                        parent_node: parent_id,
-                    },
-                    child.1,
-                );
-            }
+                .children
+                .retain(|(_, id)| !incoming_ids.contains(id));
 
-            // Good debugging spot if needed
-            // for sibling in vm_sibling_index.iter() {
-            //     dbg!(sibling);
-            // }
-
-            // parent has no parent or siblings
-            v_entities.add_component(&mut *vm_parent_index, ParentIndex { children }, parent_id);
-
-            vm_parent_index
-                .try_get(parent_id)
-                .expect("parent should have a parent index now")
+            let mut children = std::mem::take(&mut parent_index.children);
+            children.append(new_children);
+            children
         }
+        Err(_) => std::mem::take(new_children),
     };
 
-    let siblings = &mut parent_index.children;
-
-    let to_insert: SiblingID = (child_order.clone(), child_id);
-    if siblings.binary_search(&to_insert).is_err() {
-        // didn't find the sibling_id (ord + id) combo in siblings,
-        // this could mean that either the Ordered value changed, or
-        // this could mean that the entity is not present in the sibling list
-        // at all.
-
-        // remove our id, just in case it was just an "Ordered" change
-        siblings.retain(|(_, id)| id != &child_id);
-
-        // "insert_at" points to the index of the element after
-        // "insert_at - 1" points to the index of the previous element
-        let insert_at = {
-            siblings
-                .binary_search(&to_insert)
-                .expect_err("existing child")
-        };
-
-        let (prev_node_opt, next_node_opt) = {
-            (
-                if insert_at > 0 {
-                    // we have an element before to update (which becomes our previous node)
-                    Some((&siblings)[insert_at - 1].clone())
+    if children.len() > PARALLEL_SORT_THRESHOLD {
+        children.par_sort();
+    } else {
+        children.sort();
+    }
+
+    for (idx, child) in children.iter().enumerate() {
+        v_entities.add_component(
+            &mut *vm_sibling_index,
+            SiblingIndex {
+                next_sibling: if idx < children.len() - 1 {
+                    Some(children[idx + 1].clone())
                 } else {
                     None
                 },
-                if insert_at < siblings.len() {
-                    // we have an element after to update (which becomes our next node)
-                    Some((&siblings)[insert_at].clone())
+                prev_sibling: if idx > 0 {
+                    Some(children[idx - 1].clone())
                 } else {
                     None
                 },
-            )
-        };
-
-        // insert node into children as final modification to siblings
-        siblings.insert(insert_at, to_insert);
+                ordered_node: child.clone(),
+                parent_node: parent_id,
+            },
+            child.1,
+        );
+    }
 
-        // update references
-        if let Some(prev_node) = prev_node_opt {
-            // prev node should point at inserted node as next
-            (vm_sibling_index.get(prev_node.1)).next_sibling = Some(to_insert);
+    match vm_parent_index.try_get(parent_id) {
+        Ok(parent_index) => parent_index.children = children,
+        Err(_) => {
+            v_entities.add_component(&mut *vm_parent_index, ParentIndex { children }, parent_id);
         }
+    }
+}
+
+/// One frame of the explicit stack used by [`transform_with_payload`].
+struct TraversalFrame<PD, PC, PU> {
+    id: ID,
+    down: PC,
+    pending: std::collections::VecDeque<(ID, PD)>,
+    collected: Vec<PU>,
+}
 
-        if let Some(next_node) = next_node_opt {
-            // next node should point at inserted node as prev
-            (vm_sibling_index.get(next_node.1)).prev_sibling = Some(to_insert);
+/// Walks the tree rooted at `root` in a single combined top-down/bottom-up pass, threading a
+/// payload down to each node and folding a payload back up from its children.
+///
+/// `f_down` receives a node and the payload handed down from its parent (the `root_payload` for
+/// the root itself), and returns a private payload `PC` to stash for the up phase plus one `PD`
+/// per child, in the same sibling order as `ParentIndex::children`. `f_up` then receives that
+/// stashed `PC` together with the `PU`s collected from the node's children (in sibling order) and
+/// returns the `PU` handed to its parent. The walk is iterative with an explicit stack so it
+/// doesn't blow the Rust stack on deep trees.
+///
+/// Useful for single-pass jobs like computing subtree sizes, propagating layout constraints down
+/// while measured sizes bubble up, or folding a subtree into a serializable form.
+pub fn transform_with_payload<PD, PC, PU>(
+    v_parent_index: &View<ParentIndex>,
+    root: ID,
+    root_payload: PD,
+    mut f_down: impl FnMut(ID, PD) -> (PC, Vec<PD>),
+    mut f_up: impl FnMut(ID, PC, Vec<PU>) -> PU,
+) -> PU {
+    let children_of = |id: ID| -> Vec<ID> {
+        v_parent_index
+            .try_get(id)
+            .map(|parent_index| parent_index.children.iter().map(|(_, id)| *id).collect())
+            .unwrap_or_default()
+    };
+
+    let mut push_frame = |stack: &mut Vec<TraversalFrame<PD, PC, PU>>, id: ID, payload: PD| {
+        let (down, child_payloads) = f_down(id, payload);
+        let pending = child_payloads
+            .into_iter()
+            .zip(children_of(id))
+            .map(|(payload, child_id)| (child_id, payload))
+            .collect();
+        stack.push(TraversalFrame {
+            id,
+            down,
+            pending,
+            collected: Vec::new(),
+        });
+    };
+
+    let mut stack = Vec::new();
+    push_frame(&mut stack, root, root_payload);
+
+    loop {
+        let next_child = stack
+            .last_mut()
+            .expect("stack is never empty while traversing")
+            .pending
+            .pop_front();
+
+        if let Some((child_id, child_payload)) = next_child {
+            push_frame(&mut stack, child_id, child_payload);
+            continue;
         }
 
-        v_entities.add_component(
-            vm_sibling_index,
-            SiblingIndex {
-                ordered_node: to_insert,
-                next_sibling: next_node_opt,
-                prev_sibling: prev_node_opt,
-                parent_node: parent_id,
-            },
-            child_id,
-        );
+        let frame = stack.pop().expect("just checked the stack is non-empty");
+        let up = f_up(frame.id, frame.down, frame.collected);
+
+        match stack.last_mut() {
+            Some(parent) => parent.collected.push(up),
+            None => return up,
+        }
     }
 }
 
@@ -218,17 +250,112 @@ fn unlink_child(
     let parent_index = vm_parent_index.get(parent_id);
     parent_index.children.retain(|(_, id)| id != &child);
 
-    if let Some(prev_sibling_id) = t_prev_sibling {
+    if let Some(prev_sibling_id) = &t_prev_sibling {
         // prevsibling: set nextsibling to T's nextsibling
-        let mut prev_sibling_index: &mut SiblingIndex = vm_sibling_index.get(prev_sibling_id.1);
-        prev_sibling_index.next_sibling = t_next_sibling;
+        let prev_sibling_index: &mut SiblingIndex = vm_sibling_index.get(prev_sibling_id.1);
+        prev_sibling_index.next_sibling = t_next_sibling.clone();
     }
 
-    if let Some(next_sibling_id) = t_next_sibling {
+    if let Some(next_sibling_id) = &t_next_sibling {
         // nextsibling: set prevsibling to T's prevsibling
-        let mut next_sibling_index: &mut SiblingIndex = vm_sibling_index.get(next_sibling_id.1);
-        next_sibling_index.prev_sibling = t_prev_sibling;
+        let next_sibling_index: &mut SiblingIndex = vm_sibling_index.get(next_sibling_id.1);
+        next_sibling_index.prev_sibling = t_prev_sibling.clone();
     }
 
     vm_sibling_index.delete(child);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_test_world() -> World {
+        let world = World::new();
+        world.add_unique(ReorderCommands(vec![]));
+        world.add_unique(PrunedForest::default());
+        world.add_unique(DeletedChildOf::default());
+        world.run(|mut vm_child_of: ViewMut<ChildOf>| {
+            vm_child_of.update_pack();
+        });
+
+        world
+            .add_workload("tests")
+            .with_system(system!(tree_reordering))
+            .with_system(system!(tree_indexing))
+            .build();
+
+        world
+    }
+
+    #[test]
+    fn transform_with_payload_counts_subtree_sizes() {
+        let world = new_test_world();
+
+        let (root, a, b) = world.run(
+            |mut entities: EntitiesViewMut, mut vm_child_of: ViewMut<ChildOf>| {
+                let root = entities.add_entity((), ());
+                let a = entities.add_entity(&mut vm_child_of, ChildOf(root, Ordered::hinted(1)));
+                let b = entities.add_entity(&mut vm_child_of, ChildOf(root, Ordered::hinted(2)));
+                entities.add_entity(&mut vm_child_of, ChildOf(a, Ordered::hinted(1)));
+                (root, a, b)
+            },
+        );
+
+        world.run_default();
+
+        world.run(|v_parent_index: View<ParentIndex>| {
+            let count_subtree = |start: ID| {
+                transform_with_payload(
+                    &v_parent_index,
+                    start,
+                    (),
+                    |id, ()| {
+                        let num_children = v_parent_index
+                            .try_get(id)
+                            .map(|p| p.children.len())
+                            .unwrap_or(0);
+                        ((), vec![(); num_children])
+                    },
+                    |_id, (), children_counts: Vec<usize>| {
+                        1 + children_counts.iter().sum::<usize>()
+                    },
+                )
+            };
+
+            assert_eq!(count_subtree(root), 4, "root, a, b, a1");
+            assert_eq!(count_subtree(a), 2, "a, a1");
+            assert_eq!(count_subtree(b), 1, "leaf node");
+        });
+    }
+
+    #[test]
+    fn parent_index_rebuild_handles_large_batches_in_one_pass() {
+        let world = new_test_world();
+
+        let root = world.run(|mut entities: EntitiesViewMut| entities.add_entity((), ()));
+
+        // one more than PARALLEL_SORT_THRESHOLD, so the whole batch's one-time sort takes the
+        // par_sort path instead of the sequential one
+        let child_count = PARALLEL_SORT_THRESHOLD + 1;
+        world.run(
+            |mut entities: EntitiesViewMut, mut vm_child_of: ViewMut<ChildOf>| {
+                for i in 0..child_count {
+                    // reverse-ish hints so the sort actually has work to do
+                    let hint = ((child_count - i) % 256) as u8;
+                    entities.add_entity(&mut vm_child_of, ChildOf(root, Ordered::hinted(hint)));
+                }
+            },
+        );
+
+        world.run_default();
+
+        world.run(|v_parent_index: View<ParentIndex>| {
+            let rebuilt = v_parent_index.try_get(root).expect("has children");
+            assert_eq!(rebuilt.children.len(), child_count, "every child indexed");
+            assert!(
+                rebuilt.children.windows(2).all(|pair| pair[0] <= pair[1]),
+                "children should come out sorted by Ordered key"
+            );
+        });
+    }
+}
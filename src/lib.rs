@@ -14,10 +14,12 @@
 //!  - Reduce the size of the seriallized form
 //!  - Less blocking systems (if something only cares that the ChildOf / Ordering has changed and the system does not
 //!    look at the indexed outputs, then it can run concurrently with the tree_indexing system)
+pub mod ancestry;
 pub mod indexing;
 pub mod node;
 pub mod reordering;
 
+pub use ancestry::*;
 pub use indexing::*;
 pub use node::*;
 pub use reordering::*;
@@ -34,6 +36,8 @@ mod tests {
     fn test_indexing() {
         let world = World::new();
         world.add_unique(ReorderCommands(vec![]));
+        world.add_unique(PrunedForest::default());
+        world.add_unique(DeletedChildOf::default());
         world.run(|mut vm_child_of: ViewMut<ChildOf>| {
             vm_child_of.update_pack();
         });
@@ -117,10 +121,10 @@ mod tests {
 
         world.run(|mut vm_child_of: ViewMut<ChildOf>| {
             // remove should not be used
-            &mut vm_child_of.delete(a7);
-            &mut vm_child_of.delete(a4);
-            &mut vm_child_of.delete(a0);
-            &mut vm_child_of.delete(a1b);
+            vm_child_of.delete(a7);
+            vm_child_of.delete(a4);
+            vm_child_of.delete(a0);
+            vm_child_of.delete(a1b);
         });
 
         world.run_default();
@@ -1,8 +1,5 @@
 use crate::ID;
 
-pub const MAX_ORDERED: Ordered = Ordered(std::u32::MAX);
-pub const MIN_ORDERED: Ordered = Ordered(std::u32::MIN);
-
 /// ChildOf is the source of truth when it comes to the structure of things in trees.
 ///
 /// .0 is parent ID, .1 is Ordered relative to siblings
@@ -15,36 +12,150 @@ impl ChildOf {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Ordered(u32);
+/// An unbounded fractional-indexing key for ordering siblings.
+///
+/// Each byte is a base-256 "digit" after an implicit decimal point, and keys compare
+/// lexicographically (`[0x80]` < `[0x80, 0x01]` < `[0x81]`); a key sorts before any longer key it
+/// is a prefix of, even when the extra digits are `0` (`[0x80]` < `[0x80, 0x00]`). Unlike a
+/// fixed-width integer midpoint, `between` always has a digit position left to split or a position to append, so
+/// repeated insertions between the same pair of siblings never exhaust the key space the way
+/// `(min + max) / 2` over a `u32` eventually collapses to a single value.
+///
+/// `Ordered` by itself doesn't need a tie-breaker: siblings are sorted and stored as
+/// `(Ordered, ID)` pairs (see `SiblingID` in `indexing`), so two keys that do come out equal
+/// (e.g. two peers concurrently inserting "between the same pair of siblings") still resolve to
+/// a deterministic order as long as both sides agree on the entity ID.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ordered(Vec<u8>);
+
+/// Anything past this many digits is treated as "the two keys are equal here, keep descending",
+/// which can only loop this long if the caller handed `between` two keys that were already equal
+/// all the way down (a caller bug) — this is a defensive cap, not a real limit on key depth.
+const MAX_KEY_DEPTH: usize = 64;
 
 impl Ordered {
-    /// Create an ordered component with a hint of what it's initial order should be
+    /// The smallest key representable: "nothing comes before this".
+    pub fn min_value() -> Self {
+        Ordered(Vec::new())
+    }
+
+    /// A practical largest key. Arbitrary-precision keys have no true maximum (you can always
+    /// append another `0xFF`), so this is a sentinel "far end of the range" value, not a hard
+    /// ceiling.
+    pub fn max_value() -> Self {
+        Ordered(vec![0xFF])
+    }
+
+    /// Create an ordered component with a hint of what its initial order should be
     pub fn hinted(hint: u8) -> Self {
-        const EIGHTH_MAX: u32 = std::u32::MAX / 8;
-        Ordered(((hint as u32).pow(3) + (hint as u32) * 4) + EIGHTH_MAX)
+        Ordered(vec![hint])
     }
 
     /// Mutate version of "between"
     pub fn move_between(&mut self, min: &Self, max: &Self) {
-        self.0 = (min.0 / 2) + (max.0 / 2);
+        self.0 = key_between(Some(&min.0), Some(&max.0));
     }
 
     // 👇 Somewhat thought through ordering logic inspired by fractional indexing
 
     pub fn between(min: &Self, max: &Self) -> Self {
-        Ordered((min.0 / 2) + (max.0 / 2))
+        Ordered(key_between(Some(&min.0), Some(&max.0)))
     }
 
     pub fn after(a: &Self) -> Self {
-        const HALF_MAX: u32 = std::u32::MAX / 2;
-        // average between a and max
-        Ordered((a.0 / 2) + HALF_MAX)
+        Ordered(key_between(Some(&a.0), None))
     }
 
     pub fn before(a: &Self) -> Self {
-        // I know this is zero, but for posterity let's think about this conceptually as the average between a & min.
-        const HALF_MIN: u32 = std::u32::MIN / 2;
-        Ordered((a.0 / 2) + HALF_MIN)
+        Ordered(key_between(None, Some(&a.0)))
+    }
+}
+
+/// Finds the shortest digit string strictly between `lower` and `upper` (each `None` meaning
+/// "unbounded" on that side), appending a midpoint digit once the two bounds diverge by more than
+/// one digit, or descending another digit position when they don't.
+fn key_between(lower: Option<&[u8]>, upper: Option<&[u8]>) -> Vec<u8> {
+    let lower = lower.unwrap_or(&[]);
+    // Once a digit we pick is merely one below `upper`'s digit (the `else` branch below), the
+    // result is already strictly less than `upper` -- nothing deeper needs to respect `upper`'s
+    // remaining digits any more, so treat it as unbounded above from that point on. Without this,
+    // `upper`'s padding (`unwrap_or(&0)` past its own length) reads as a hard ceiling of 0 that
+    // can never be exceeded, forcing the loop all the way to `MAX_KEY_DEPTH` for ordinary keys
+    // like `before(hinted(1))`.
+    let mut upper_active = upper.is_some();
+    let upper = upper.unwrap_or(&[]);
+
+    let mut result = Vec::new();
+    for index in 0..MAX_KEY_DEPTH {
+        let lo_digit = *lower.get(index).unwrap_or(&0) as u16;
+        let hi_digit = if upper_active {
+            *upper.get(index).unwrap_or(&0) as u16
+        } else {
+            0x100 // already below `upper`: treat this digit as "past 0xFF"
+        };
+
+        if hi_digit - lo_digit > 1 {
+            result.push((lo_digit + (hi_digit - lo_digit) / 2) as u8);
+            return result;
+        }
+
+        if hi_digit != lo_digit {
+            upper_active = false;
+        }
+        result.push(lo_digit as u8);
+
+        // `lower` and `upper` have compared equal at every digit so far, and neither has a digit
+        // left to diverge on: they're exactly equal (see the caller-bug fallback below), so stop
+        // here instead of looping all the way to `MAX_KEY_DEPTH`
+        if upper_active && index >= lower.len() && index >= upper.len() {
+            break;
+        }
+    }
+
+    // only reachable if `lower` and `upper` compared equal at every digit visited above, which
+    // would mean the caller asked for a key strictly between two equal keys -- there is no such
+    // key, so this is a defensive fallback for a caller bug, not a real code path
+    debug_assert!(false, "Ordered::between called with two equal keys");
+    result.push(0x80);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn before_and_after_chains_stay_short() {
+        assert!(
+            Ordered::before(&Ordered::hinted(1)).0.len() < 10,
+            "a single before() near the low end should not need many digits"
+        );
+
+        let mut key = Ordered::hinted(1);
+        for _ in 0..20 {
+            key = Ordered::before(&key);
+            assert!(
+                key.0.len() < 10,
+                "repeated before() should not blow up the key length: {:?}",
+                key.0
+            );
+        }
+
+        let mut key = Ordered::hinted(254);
+        for _ in 0..20 {
+            key = Ordered::after(&key);
+            assert!(
+                key.0.len() < 10,
+                "repeated after() should not blow up the key length: {:?}",
+                key.0
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "equal")]
+    fn between_equal_keys_is_a_caller_bug() {
+        let key = Ordered::hinted(5);
+        Ordered::between(&key, &key);
     }
 }
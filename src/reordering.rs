@@ -1,16 +1,44 @@
 use crate::*;
 use shipyard::*;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Clone)]
 pub enum ReorderCmd {
-    Move { entity: ID, between: (ID, ID) },
+    /// Moves `entity` to a new position, anchored between `between.0` (its new previous sibling)
+    /// and `between.1` (its new next sibling). Either side may be `None` to mean "no neighbor
+    /// there, anchor against this side being unbounded" (inserting as the first/last child);
+    /// both must not be `None` at once.
+    Move {
+        entity: ID,
+        between: (Option<ID>, Option<ID>),
+    },
+    /// Detaches `entity` (and its whole subtree) from the tree without losing its shape: the
+    /// subtree's `ChildOf` values are stashed in `PrunedForest` rather than discarded, so it can
+    /// later be restored with `Reattach`.
+    Prune { entity: ID },
+    /// Restores a subtree previously taken out with `Prune`, re-linking `entity` between the two
+    /// given siblings and recomputing only its own `Ordered`; the rest of the subtree keeps the
+    /// shape it had when it was pruned.
+    Reattach { entity: ID, between: (ID, ID) },
 }
 
 /// Unique storage of commands for reordering
 pub struct ReorderCommands(pub Vec<ReorderCmd>);
 
+/// Unique storage holding subtrees taken out of the tree by `ReorderCmd::Prune`, keyed by the
+/// pruned root's entity id, so `Reattach` can restore exactly what was removed (undo-able
+/// deletes, "move to trash / restore" semantics).
+#[derive(Default)]
+pub struct PrunedForest(pub HashMap<ID, Vec<(ID, ChildOf)>>);
+
 pub fn tree_reordering(
-    (mut commands, mut child_of): (UniqueViewMut<ReorderCommands>, ViewMut<ChildOf>),
+    (v_entities, mut commands, mut pruned_forest, v_parent_index, mut child_of): (
+        EntitiesView,
+        UniqueViewMut<ReorderCommands>,
+        UniqueViewMut<PrunedForest>,
+        View<ParentIndex>,
+        ViewMut<ChildOf>,
+    ),
 ) {
     for cmd in commands.0.drain(..) {
         match cmd {
@@ -18,40 +46,481 @@ pub fn tree_reordering(
                 entity: target,
                 between: (a, b),
             } => {
-                let (target_parent, target_after, target_before) = {
-                    // check that a & b are both of the same parent
-                    let ChildOf(a_of, a_ord) = (&child_of).get(a);
-                    let ChildOf(b_of, mut b_ord) = (&child_of).get(b);
-
-                    if a_of != b_of {
-                        eprintln!("reorder between targets two elements of different parents target={:?}; {:?} vs {:?}", target, a_of, b_of);
-                        // Future: take a_of and try to insert directly after
-                        // we would have to look up all children a_of
-                        b_ord = (&child_of)
-                            .iter()
-                            .filter(|ChildOf(e_of, e_ord)| e_of == a_of && a_ord < e_ord)
-                            .fold(
-                                // default to farthest away which will be immediately replaced
-                                MAX_ORDERED,
-                                |after, ChildOf(_, e_ord)| {
-                                    if *e_ord < after {
-                                        // e_ord is closer than previous after
-                                        *e_ord
-                                    } else {
-                                        after
-                                    }
-                                },
-                            );
-                    }
+                let (target_parent, new_ordered) = match (a, b) {
+                    (Some(a), Some(b)) => {
+                        // check that a & b are both of the same parent
+                        let ChildOf(a_of, a_ord) = (&child_of).get(a);
+                        let ChildOf(b_of, b_ord) = (&child_of).get(b);
+                        let mut b_ord = b_ord.clone();
+
+                        if a_of != b_of {
+                            eprintln!("reorder between targets two elements of different parents target={:?}; {:?} vs {:?}", target, a_of, b_of);
+                            // Future: take a_of and try to insert directly after
+                            // we would have to look up all children a_of
+                            b_ord = (&child_of)
+                                .iter()
+                                .filter(|ChildOf(e_of, e_ord)| e_of == a_of && a_ord < e_ord)
+                                .fold(
+                                    // default to the far end of the range (or just past `a_ord`
+                                    // when `a_ord` already lies beyond it), which any closer
+                                    // sibling after `a_ord` replaces
+                                    Ordered::max_value().max(Ordered::after(a_ord)),
+                                    |after, ChildOf(_, e_ord)| {
+                                        if *e_ord < after {
+                                            // e_ord is closer than previous after
+                                            e_ord.clone()
+                                        } else {
+                                            after
+                                        }
+                                    },
+                                );
+                        }
 
-                    (*a_of, a_ord.clone(), b_ord.clone())
+                        (*a_of, Ordered::between(a_ord, &b_ord))
+                    }
+                    // only a lower neighbor settled: anchor directly after it. Feeding it as both
+                    // bounds of `Ordered::between` would collapse to the degenerate, equal-to-`a`
+                    // key that `between(x, x)` produces
+                    (Some(a), None) => {
+                        let ChildOf(a_of, a_ord) = (&child_of).get(a);
+                        (*a_of, Ordered::after(a_ord))
+                    }
+                    // only an upper neighbor settled: anchor directly before it
+                    (None, Some(b)) => {
+                        let ChildOf(b_of, b_ord) = (&child_of).get(b);
+                        (*b_of, Ordered::before(b_ord))
+                    }
+                    (None, None) => {
+                        eprintln!("reorder for {:?} has no settled neighbor on either side", target);
+                        continue;
+                    }
                 };
 
                 // update position of the child
-                let mut target_child_of: &mut ChildOf = (&mut child_of).get(target);
+                let target_child_of: &mut ChildOf = (&mut child_of).get(target);
                 target_child_of.0 = target_parent;
-                target_child_of.1 = Ordered::between(&target_after, &target_before);
+                target_child_of.1 = new_ordered;
+            }
+
+            ReorderCmd::Prune { entity } => {
+                if pruned_forest.0.contains_key(&entity) {
+                    eprintln!("prune for an entity that's already pruned: {:?}", entity);
+                    continue;
+                }
+
+                let subtree = collect_subtree(&v_parent_index, &child_of, entity);
+
+                for (id, _) in &subtree {
+                    child_of.delete(*id);
+                }
+
+                pruned_forest.0.insert(entity, subtree);
             }
+
+            ReorderCmd::Reattach {
+                entity,
+                between: (a, b),
+            } => {
+                let subtree = match pruned_forest.0.remove(&entity) {
+                    Some(subtree) => subtree,
+                    None => {
+                        eprintln!("reattach for an entity that wasn't pruned: {:?}", entity);
+                        continue;
+                    }
+                };
+
+                for (id, stored_child_of) in subtree {
+                    let child_of_to_insert = if id == entity {
+                        // only the reattached root's position needs recomputing; descendants
+                        // keep the `Ordered` they had when they were pruned
+                        let ChildOf(a_of, a_ord) = (&child_of).get(a);
+                        let ChildOf(_, b_ord) = (&child_of).get(b);
+                        ChildOf(*a_of, Ordered::between(a_ord, b_ord))
+                    } else {
+                        stored_child_of
+                    };
+
+                    v_entities.add_component(&mut child_of, child_of_to_insert, id);
+                }
+            }
+        }
+    }
+}
+
+/// Collects `root` and every descendant's current `(ID, ChildOf)`, read off of `ParentIndex`
+/// before anything is unlinked.
+fn collect_subtree(
+    v_parent_index: &View<ParentIndex>,
+    v_child_of: &ViewMut<ChildOf>,
+    root: ID,
+) -> Vec<(ID, ChildOf)> {
+    let mut collected = Vec::new();
+    let mut stack = vec![root];
+
+    while let Some(id) = stack.pop() {
+        match v_child_of.try_get(id) {
+            Ok(child_of) => collected.push((id, child_of.clone())),
+            // `root` itself may be one of several independent top-level/rootless subtrees and so
+            // have no `ChildOf` of its own; still record it with a placeholder so it comes back
+            // out of `PrunedForest` on Reattach, which always recomputes the reattached root's
+            // own `ChildOf` from its new anchors (see the `id == entity` branch above) and never
+            // reads this placeholder's value
+            Err(_) if id == root => collected.push((id, ChildOf(id, Ordered::min_value()))),
+            Err(_) => {}
+        }
+
+        if let Ok(parent_index) = v_parent_index.try_get(id) {
+            stack.extend(parent_index.children.iter().map(|(_, child_id)| *child_id));
         }
     }
+
+    collected
+}
+
+/// Computes the minimal set of `ReorderCmd::Move` needed to reshape `parent`'s children into
+/// `target` order and pushes them onto `commands`, to be applied by `tree_reordering`.
+///
+/// Uses keyed reconciliation: children are matched to `target` by `ID`, and the Longest
+/// Increasing Subsequence of their current positions (read off of `ParentIndex`) is the largest
+/// run that's already in the right relative order, so those never move. Everything off the LIS
+/// gets exactly one `Move`, placed between its nearest still-settled neighbors and emitted in
+/// target order, where "settled" starts as the LIS members (which by construction never move)
+/// and grows to include each entity as its own `Move` is emitted -- so an entity several positions
+/// off the LIS anchors against the *previous* entity's freshly-queued position rather than
+/// colliding with it on the same anchor. `tree_reordering` applies commands in the order they were
+/// pushed, so by the time it resolves a later `Move`'s anchor, an earlier one in the same batch
+/// has already landed at its new `Ordered`. This minimizes writes to `ChildOf.1` when syncing to a
+/// tree shape coming from, e.g., a remote peer or a rebuilt view, instead of naively reassigning
+/// every sibling's order. When none of `target`'s entries is currently a child of `parent`, there
+/// is no settled neighbor to anchor against and nothing is pushed.
+pub fn diff_children_to_target(
+    v_parent_index: &View<ParentIndex>,
+    commands: &mut ReorderCommands,
+    parent: ID,
+    target: &[ID],
+) {
+    let current_position: HashMap<ID, usize> = v_parent_index
+        .try_get(parent)
+        .map(|parent_index| {
+            parent_index
+                .children
+                .iter()
+                .enumerate()
+                .map(|(position, (_, id))| (*id, position))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // (target index, current position) pairs for children that exist in both snapshots; a
+    // matching run of these in ascending current-position order is an LIS candidate.
+    let matched: Vec<(usize, usize)> = target
+        .iter()
+        .enumerate()
+        .filter_map(|(target_index, id)| {
+            current_position.get(id).map(|&position| (target_index, position))
+        })
+        .collect();
+
+    let current_positions_in_target_order: Vec<usize> =
+        matched.iter().map(|&(_, position)| position).collect();
+
+    // grows as moves are emitted below, so a later move can anchor against an earlier one in the
+    // same batch instead of only ever the original LIS members
+    let mut settled: HashSet<usize> =
+        longest_increasing_subsequence_indices(&current_positions_in_target_order)
+            .into_iter()
+            .map(|matched_index| matched[matched_index].0)
+            .collect();
+
+    for (target_index, &entity) in target.iter().enumerate() {
+        if settled.contains(&target_index) {
+            continue;
+        }
+
+        let prev = (0..target_index).rev().find(|i| settled.contains(i));
+        let next = (target_index + 1..target.len()).find(|i| settled.contains(i));
+
+        let between = match (prev, next) {
+            (Some(prev), Some(next)) => (Some(target[prev]), Some(target[next])),
+            // nothing settled on one side: anchor against the single settled neighbor we do have,
+            // leaving the other side unbounded
+            (Some(prev), None) => (Some(target[prev]), None),
+            (None, Some(next)) => (None, Some(target[next])),
+            // no settled child anywhere: only possible when none of `target`'s entries is
+            // currently a child of `parent` (an empty `target` included); such entries get no move
+            (None, None) => continue,
+        };
+
+        commands.0.push(ReorderCmd::Move {
+            entity,
+            between,
+        });
+        settled.insert(target_index);
+    }
+}
+
+/// Indices (into `seq`) of one longest strictly increasing subsequence, found via the standard
+/// O(n log n) patience-sorting algorithm.
+fn longest_increasing_subsequence_indices(seq: &[usize]) -> Vec<usize> {
+    let mut pile_tops: Vec<usize> = Vec::new(); // seq-index of the smallest tail of each pile length
+    let mut predecessor: Vec<Option<usize>> = vec![None; seq.len()];
+
+    for (i, &value) in seq.iter().enumerate() {
+        let pos = pile_tops.partition_point(|&pile_top| seq[pile_top] < value);
+
+        predecessor[i] = if pos > 0 { Some(pile_tops[pos - 1]) } else { None };
+
+        if pos == pile_tops.len() {
+            pile_tops.push(i);
+        } else {
+            pile_tops[pos] = i;
+        }
+    }
+
+    let mut lis = Vec::with_capacity(pile_tops.len());
+    let mut cursor = pile_tops.last().copied();
+    while let Some(i) = cursor {
+        lis.push(i);
+        cursor = predecessor[i];
+    }
+    lis.reverse();
+    lis
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_test_world() -> World {
+        let world = World::new();
+        world.add_unique(ReorderCommands(vec![]));
+        world.add_unique(PrunedForest::default());
+        world.add_unique(DeletedChildOf::default());
+        world.run(|mut vm_child_of: ViewMut<ChildOf>| {
+            vm_child_of.update_pack();
+        });
+
+        world
+            .add_workload("tests")
+            .with_system(system!(tree_reordering))
+            .with_system(system!(tree_indexing))
+            .build();
+
+        world
+    }
+
+    fn children_of(v_parent_index: &View<ParentIndex>, parent: ID) -> Vec<ID> {
+        v_parent_index
+            .try_get(parent)
+            .expect("has children")
+            .children
+            .iter()
+            .map(|(_, id)| *id)
+            .collect()
+    }
+
+    #[test]
+    fn diff_children_to_target_reverses_siblings() {
+        let world = new_test_world();
+
+        let (parent, c1, c2, c3) = world.run(
+            |mut entities: EntitiesViewMut, mut vm_child_of: ViewMut<ChildOf>| {
+                let parent = entities.add_entity((), ());
+                let c1 = entities.add_entity(&mut vm_child_of, ChildOf(parent, Ordered::hinted(1)));
+                let c2 = entities.add_entity(&mut vm_child_of, ChildOf(parent, Ordered::hinted(2)));
+                let c3 = entities.add_entity(&mut vm_child_of, ChildOf(parent, Ordered::hinted(3)));
+                (parent, c1, c2, c3)
+            },
+        );
+
+        world.run_default();
+
+        world.run(
+            |v_parent_index: View<ParentIndex>, mut commands: UniqueViewMut<ReorderCommands>| {
+                diff_children_to_target(&v_parent_index, &mut commands, parent, &[c3, c2, c1]);
+            },
+        );
+
+        world.run_default();
+
+        world.run(|v_parent_index: View<ParentIndex>| {
+            assert_eq!(
+                children_of(&v_parent_index, parent),
+                vec![c3, c2, c1],
+                "should match the target order exactly, not collapse to a no-op"
+            );
+        });
+    }
+
+    #[test]
+    fn prune_then_reattach_does_not_panic_on_next_reindex() {
+        let world = new_test_world();
+
+        let (parent, a, b, b1, c) = world.run(
+            |mut entities: EntitiesViewMut, mut vm_child_of: ViewMut<ChildOf>| {
+                let parent = entities.add_entity((), ());
+                let a = entities.add_entity(&mut vm_child_of, ChildOf(parent, Ordered::hinted(1)));
+                let b = entities.add_entity(&mut vm_child_of, ChildOf(parent, Ordered::hinted(2)));
+                let b1 = entities.add_entity(&mut vm_child_of, ChildOf(b, Ordered::hinted(1)));
+                let c = entities.add_entity(&mut vm_child_of, ChildOf(parent, Ordered::hinted(3)));
+                (parent, a, b, b1, c)
+            },
+        );
+
+        world.run_default();
+
+        world.run(|mut commands: UniqueViewMut<ReorderCommands>| {
+            commands.0.push(ReorderCmd::Prune { entity: b });
+        });
+        world.run_default();
+
+        world.run(|mut commands: UniqueViewMut<ReorderCommands>| {
+            commands.0.push(ReorderCmd::Reattach {
+                entity: b,
+                between: (a, c),
+            });
+        });
+
+        // previously panicked here: the ChildOf deletion recorded by Prune was never drained
+        // from tree_indexing's deleted() buffer, so it resurfaced on this run and tried to
+        // unlink `b` a second time before its SiblingIndex had been rebuilt
+        world.run_default();
+
+        world.run(
+            |v_parent_index: View<ParentIndex>, v_sibling_index: View<SiblingIndex>| {
+                assert_eq!(
+                    children_of(&v_parent_index, parent),
+                    vec![a, b, c],
+                    "b should be reattached between a and c"
+                );
+                assert_eq!(
+                    children_of(&v_parent_index, b),
+                    vec![b1],
+                    "b1 should still be b's child, unaffected by the round trip"
+                );
+
+                v_sibling_index.try_get(b).expect("b should be reindexed");
+                v_sibling_index
+                    .try_get(b1)
+                    .expect("b1 should still be reindexed");
+            },
+        );
+    }
+
+    #[test]
+    fn prune_then_reattach_a_rootless_subtree_restores_its_child_of() {
+        let world = new_test_world();
+
+        let (root2, c1) = world.run(
+            |mut entities: EntitiesViewMut, mut vm_child_of: ViewMut<ChildOf>| {
+                // root2 has no ChildOf of its own: a second, independent top-level subtree
+                let root2 = entities.add_entity((), ());
+                let c1 = entities.add_entity(&mut vm_child_of, ChildOf(root2, Ordered::hinted(1)));
+                (root2, c1)
+            },
+        );
+
+        let (other_parent, a, b) = world.run(
+            |mut entities: EntitiesViewMut, mut vm_child_of: ViewMut<ChildOf>| {
+                let other_parent = entities.add_entity((), ());
+                let a = entities.add_entity(
+                    &mut vm_child_of,
+                    ChildOf(other_parent, Ordered::hinted(1)),
+                );
+                let b = entities.add_entity(
+                    &mut vm_child_of,
+                    ChildOf(other_parent, Ordered::hinted(2)),
+                );
+                (other_parent, a, b)
+            },
+        );
+
+        world.run_default();
+
+        world.run(|mut commands: UniqueViewMut<ReorderCommands>| {
+            commands.0.push(ReorderCmd::Prune { entity: root2 });
+        });
+        world.run_default();
+
+        world.run(|mut commands: UniqueViewMut<ReorderCommands>| {
+            commands.0.push(ReorderCmd::Reattach {
+                entity: root2,
+                between: (a, b),
+            });
+        });
+        world.run_default();
+
+        world.run(|v_child_of: View<ChildOf>| {
+            let ChildOf(parent, _) = v_child_of
+                .try_get(root2)
+                .expect("root2 should have a ChildOf again after being reattached");
+            assert_eq!(*parent, other_parent);
+        });
+
+        world.run(|v_parent_index: View<ParentIndex>| {
+            assert_eq!(
+                children_of(&v_parent_index, root2),
+                vec![c1],
+                "root2's own subtree should be untouched by the round trip"
+            );
+        });
+    }
+
+    #[test]
+    fn pruning_an_already_pruned_entity_does_not_clobber_the_stashed_subtree() {
+        let world = new_test_world();
+
+        let (other_parent, x, y, a, a1) = world.run(
+            |mut entities: EntitiesViewMut, mut vm_child_of: ViewMut<ChildOf>| {
+                let other_parent = entities.add_entity((), ());
+                let x = entities.add_entity(
+                    &mut vm_child_of,
+                    ChildOf(other_parent, Ordered::hinted(1)),
+                );
+                let y = entities.add_entity(
+                    &mut vm_child_of,
+                    ChildOf(other_parent, Ordered::hinted(3)),
+                );
+                let parent = entities.add_entity((), ());
+                let a = entities.add_entity(&mut vm_child_of, ChildOf(parent, Ordered::hinted(1)));
+                let a1 = entities.add_entity(&mut vm_child_of, ChildOf(a, Ordered::hinted(1)));
+                (other_parent, x, y, a, a1)
+            },
+        );
+
+        world.run_default();
+
+        world.run(|mut commands: UniqueViewMut<ReorderCommands>| {
+            commands.0.push(ReorderCmd::Prune { entity: a });
+        });
+        world.run_default();
+
+        // pruning again before the matching Reattach should be a no-op, not overwrite the
+        // already-stashed (and now unreachable) subtree with an empty one
+        world.run(|mut commands: UniqueViewMut<ReorderCommands>| {
+            commands.0.push(ReorderCmd::Prune { entity: a });
+        });
+        world.run_default();
+
+        world.run(|mut commands: UniqueViewMut<ReorderCommands>| {
+            commands.0.push(ReorderCmd::Reattach {
+                entity: a,
+                between: (x, y),
+            });
+        });
+        world.run_default();
+
+        world.run(|v_parent_index: View<ParentIndex>| {
+            assert_eq!(
+                children_of(&v_parent_index, other_parent),
+                vec![x, a, y],
+                "a should be reattached between x and y"
+            );
+            assert_eq!(
+                children_of(&v_parent_index, a),
+                vec![a1],
+                "a1 should have survived the redundant Prune"
+            );
+        });
+    }
 }